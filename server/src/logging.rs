@@ -8,52 +8,180 @@ use log::*;
 use log4rs::{
     append::{
         rolling_file::{
-            policy::compound::{roll::Roll, trigger::size::SizeTrigger, CompoundPolicy},
-            RollingFileAppender,
+            policy::compound::{
+                roll::Roll,
+                trigger::{size::SizeTrigger, Trigger},
+                CompoundPolicy,
+            },
+            LogFile, RollingFileAppender,
         },
         Append,
     },
     config::{Appender, Config, Root},
-    encode::pattern::PatternEncoder,
+    encode::Encode,
     filter::{Filter, Response},
 };
+#[cfg(not(feature = "json_logs"))]
+use log4rs::encode::pattern::PatternEncoder;
 use std::{
     error::Error,
     fmt,
     fs::{read_dir, remove_file, rename, File},
     io,
     path::Path,
-    sync::{Arc, Mutex},
+    sync::{Arc, Mutex, RwLock},
     thread,
 };
+use tokio::sync::broadcast;
+
+#[cfg(feature = "json_logs")]
+use std::io::Write as _;
 
 #[cfg(unix)]
 use termion::color;
 
 const FILE_SIZE_LIMIT: u64 = 50_000_000;
 
+// Defaults for the compressed-archive retention pass; chosen to keep a long
+// history without letting `.log.gz` files accumulate unbounded on disk.
+const DEFAULT_MAX_LOG_AGE_DAYS: i64 = 30;
+const DEFAULT_MAX_LOG_FILES: usize = 50;
+
 #[cfg(debug_assertions)]
 const LEVEL_FILTER: LevelFilter = LevelFilter::Debug;
 #[cfg(not(debug_assertions))]
 const LEVEL_FILTER: LevelFilter = LevelFilter::Info;
 
+// A set of per-module log-level rules consulted at runtime by `CrateFilter`.
+// The `root` level applies to any module that doesn't match a rule; `rules`
+// are kept sorted longest-prefix-first so the first match also wins.
+pub struct FilterRules {
+    root: LevelFilter,
+    rules: Vec<(String, LevelFilter)>,
+}
+
+// Shared between the live `log` console command and every appender's filter.
+pub type SharedFilter = Arc<RwLock<FilterRules>>;
+
+// How many log events the broadcast channel buffers before slow subscribers
+// start missing records (they're told how many via `RecvError::Lagged`).
+const LOG_BROADCAST_CAPACITY: usize = 256;
+
+// A single formatted log record published to admin log-tailing sockets.
+#[derive(Clone)]
+pub struct LogEvent {
+    pub level: String,
+    pub timestamp: String,
+    pub target: String,
+    pub message: String,
+}
+
+// Everything `main` needs to keep after logging is initialised: the live filter
+// driving the `log` command and the channel feeding `/ws/logs` subscribers.
+pub struct Logger {
+    pub filter: SharedFilter,
+    pub log_events: broadcast::Sender<LogEvent>,
+}
+
+impl FilterRules {
+    // Parses a comma-separated filter spec like
+    // `info,server::packets=debug,server::logging=warn`. The bare leading
+    // token sets the root level; `module=level` tokens add prefix rules.
+    // Unparseable tokens are ignored so a typo can't silence the server.
+    fn parse(spec: &str) -> FilterRules {
+        let mut root = LEVEL_FILTER;
+        let mut rules = Vec::new();
+
+        for token in spec.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+            match token.split_once('=') {
+                Some((module, level)) =>
+                    if let Ok(level) = level.trim().parse::<LevelFilter>() {
+                        rules.push((module.trim().to_owned(), level));
+                    },
+                None =>
+                    if let Ok(level) = token.parse::<LevelFilter>() {
+                        root = level;
+                    },
+            }
+        }
+
+        // Longest prefix first so `level_for` can return on the first match.
+        rules.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+
+        FilterRules { root, rules }
+    }
+
+    // The level governing a record from the given module: the longest matching
+    // prefix rule, falling back to the root level.
+    fn level_for(&self, module: Option<&str>) -> LevelFilter {
+        if let Some(path) = module {
+            for (prefix, level) in &self.rules {
+                if let Some(rest) = path.strip_prefix(prefix.as_str()) {
+                    if rest.is_empty() || rest.starts_with("::") {
+                        return *level;
+                    }
+                }
+            }
+        }
+        self.root
+    }
+
+    // The most permissive level across the root and every rule, used to set the
+    // global max level so records aren't dropped before reaching an appender.
+    fn max_level(&self) -> LevelFilter {
+        self.rules
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.root, std::cmp::max)
+    }
+}
+
+// Replaces the shared filter rules from a live `log <spec>` command and
+// reapplies the global max level so upstream filtering matches.
+pub fn reconfigure(filter: &SharedFilter, spec: &str) {
+    let rules = FilterRules::parse(spec);
+    let max_level = rules.max_level();
+    *filter.write().expect("Logger filter lock poisoned.") = rules;
+    log::set_max_level(max_level);
+}
+
 // Sets up log4rs customized for the server
 pub fn init_logger(
     console_interface: Arc<Interface<DefaultTerminal>>,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<Logger, Box<dyn Error>> {
+    let filter = Arc::new(RwLock::new(FilterRules {
+        root: LEVEL_FILTER,
+        rules: Vec::new(),
+    }));
     // Logs info to the console with colors and such
     let console = CustomConsoleAppender { console_interface };
 
+    // Fans each record out to remote admins tailing `/ws/logs`.
+    let (log_events, _) = broadcast::channel(LOG_BROADCAST_CAPACITY);
+    let broadcast = BroadcastAppender {
+        sender: log_events.clone(),
+    };
+
     // Logs to log files
     let log_file = RollingFileAppender::builder()
-        .encoder(Box::new(PatternEncoder::new(
-            "[{d(%H:%M:%S)(local)} {l}]: {m}\n",
-        )))
+        .encoder(file_encoder())
         .build(
             "logs/latest.log",
             Box::new(CompoundPolicy::new(
-                Box::new(SizeTrigger::new(FILE_SIZE_LIMIT)),
-                Box::new(CustomLogRoller::new()),
+                Box::new(roll_trigger()),
+                Box::new(CustomLogRoller::new("latest")),
+            )),
+        )?;
+
+    // Mirrors the everything-log but only keeps Warn-and-above records so an
+    // operator can triage incidents without grepping the full log.
+    let error_file = RollingFileAppender::builder()
+        .encoder(file_encoder())
+        .build(
+            "logs/errors.log",
+            Box::new(CompoundPolicy::new(
+                Box::new(roll_trigger()),
+                Box::new(CustomLogRoller::new("errors")),
             )),
         )?;
 
@@ -61,30 +189,47 @@ pub fn init_logger(
     let config = Config::builder()
         .appender(
             Appender::builder()
-                .filter(Box::new(CrateFilter))
+                .filter(Box::new(CrateFilter::new(filter.clone())))
                 .build("console", Box::new(console)),
         )
         .appender(
             Appender::builder()
-                .filter(Box::new(CrateFilter))
+                .filter(Box::new(CrateFilter::new(filter.clone())))
                 .build("log_file", Box::new(log_file)),
         )
+        .appender(
+            Appender::builder()
+                .filter(Box::new(CrateFilter::new(filter.clone())))
+                .filter(Box::new(LevelRangeFilter::new(LevelFilter::Warn)))
+                .build("error_file", Box::new(error_file)),
+        )
+        .appender(
+            Appender::builder()
+                .filter(Box::new(CrateFilter::new(filter.clone())))
+                .build("broadcast", Box::new(broadcast)),
+        )
         .build(
             Root::builder()
                 .appender("console")
                 .appender("log_file")
-                .build(LEVEL_FILTER),
+                .appender("error_file")
+                .appender("broadcast")
+                .build(LevelFilter::Trace),
         )?;
 
     log4rs::init_config(config)?;
+    // The appenders do the real level filtering, so let every record that any
+    // rule permits reach them.
+    log::set_max_level(filter.read().expect("Logger filter lock poisoned.").max_level());
 
-    Ok(())
+    Ok(Logger { filter, log_events })
 }
 
-// Called at the end of main, compresses the last log file
+// Called at the end of main, compresses the last log files
 pub fn cleanup() {
     // There's no reason to handle an error here
-    let _ = CustomLogRoller::new().roll_threaded(Path::new("./logs/latest.log"), false);
+    let _ = CustomLogRoller::new("latest").roll_threaded(Path::new("./logs/latest.log"), false);
+    let _ = CustomLogRoller::new("errors").roll_threaded(Path::new("./logs/errors.log"), false);
 }
 
 #[inline]
@@ -92,30 +237,177 @@ fn current_time() -> DateTime<chrono_tz::Tz> {
     Utc::now().with_timezone(&Eastern)
 }
 
-// Only allow logging from out crate
-struct CrateFilter;
+// The encoder for the rolling file appenders. Builds with the human-readable
+// pattern by default, or with `JsonEncoder` (one JSON object per line) when the
+// `json_logs` feature is enabled, for aggregation by log tooling. The console
+// appender keeps its colored output either way.
+#[cfg(feature = "json_logs")]
+fn file_encoder() -> Box<dyn Encode> {
+    Box::new(JsonEncoder)
+}
+
+#[cfg(not(feature = "json_logs"))]
+fn file_encoder() -> Box<dyn Encode> {
+    Box::new(PatternEncoder::new("[{d(%H:%M:%S)(local)} {l}]: {m}\n"))
+}
+
+// Emits one JSON object per record with the fields expected by structured log
+// stores: an RFC3339 timestamp in the configured zone, level, module, message.
+#[cfg(feature = "json_logs")]
+struct JsonEncoder;
+
+#[cfg(feature = "json_logs")]
+impl Encode for JsonEncoder {
+    fn encode(&self, w: &mut dyn log4rs::encode::Write, record: &Record) -> anyhow::Result<()> {
+        let entry = serde_json::json!({
+            "timestamp": current_time().to_rfc3339(),
+            "level": record.level().to_string(),
+            "module": record.module_path().unwrap_or_else(|| record.target()),
+            "message": record.args().to_string(),
+        });
+        serde_json::to_writer(&mut *w, &entry)?;
+        w.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "json_logs")]
+impl fmt::Debug for JsonEncoder {
+    fn fmt(&self, _f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        Ok(())
+    }
+}
+
+// Builds the trigger driving a roll: a size limit OR a day change, whichever
+// hits first, so a low-traffic server still gets one archive per calendar day.
+fn roll_trigger() -> AnyTrigger {
+    AnyTrigger {
+        triggers: vec![
+            Box::new(SizeTrigger::new(FILE_SIZE_LIMIT)),
+            Box::new(TimeTrigger::new()),
+        ],
+    }
+}
+
+// Fires a roll once per calendar day (in the configured Eastern zone) by
+// remembering the last day it saw and comparing against the current ordinal.
+struct TimeTrigger {
+    last_day: Mutex<u32>,
+}
+
+impl TimeTrigger {
+    fn new() -> Self {
+        TimeTrigger {
+            last_day: Mutex::new(current_time().ordinal()),
+        }
+    }
+}
+
+impl Trigger for TimeTrigger {
+    fn trigger(&self, _file: &LogFile) -> anyhow::Result<bool> {
+        let today = current_time().ordinal();
+        let mut last_day = match self.last_day.lock() {
+            Ok(g) => g,
+            // Privately managed and never held across a panic point.
+            Err(_) => unreachable!("Logger trigger mutex poisoned."),
+        };
+
+        if *last_day != today {
+            *last_day = today;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+impl fmt::Debug for TimeTrigger {
+    fn fmt(&self, _f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        Ok(())
+    }
+}
+
+// Fires when any of its inner triggers fire. Every trigger is evaluated on each
+// call (no short-circuit) so stateful triggers like `TimeTrigger` always update.
+struct AnyTrigger {
+    triggers: Vec<Box<dyn Trigger>>,
+}
+
+impl Trigger for AnyTrigger {
+    fn trigger(&self, file: &LogFile) -> anyhow::Result<bool> {
+        let mut fire = false;
+        for trigger in &self.triggers {
+            if trigger.trigger(file)? {
+                fire = true;
+            }
+        }
+        Ok(fire)
+    }
+}
+
+impl fmt::Debug for AnyTrigger {
+    fn fmt(&self, _f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        Ok(())
+    }
+}
+
+// Applies the live per-module level rules: a record is rejected when its level
+// is more verbose than the level configured for its module, accepted otherwise.
+struct CrateFilter {
+    filter: SharedFilter,
+}
+
+impl CrateFilter {
+    fn new(filter: SharedFilter) -> Self {
+        CrateFilter { filter }
+    }
+}
 
 impl Filter for CrateFilter {
-    #[cfg(debug_assertions)]
     fn filter(&self, record: &Record) -> Response {
-        match record.module_path() {
-            Some(path) =>
-                if path.starts_with("server") {
-                    Response::Accept
-                } else {
-                    Response::Reject
-                },
-            None => Response::Reject,
+        let level = self
+            .filter
+            .read()
+            .expect("Logger filter lock poisoned.")
+            .level_for(record.module_path());
+
+        if record.level().to_level_filter() <= level {
+            Response::Neutral
+        } else {
+            Response::Reject
         }
     }
+}
 
-    #[cfg(not(debug_assertions))]
-    fn filter(&self, _record: &Record) -> Response {
-        Response::Neutral
+impl fmt::Debug for CrateFilter {
+    fn fmt(&self, _f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        Ok(())
     }
 }
 
-impl fmt::Debug for CrateFilter {
+// Rejects records below `min_level`, passing everything at or above it through.
+// Paired with an appender to carve a severity band out of the full log stream.
+struct LevelRangeFilter {
+    min_level: LevelFilter,
+}
+
+impl LevelRangeFilter {
+    fn new(min_level: LevelFilter) -> Self {
+        LevelRangeFilter { min_level }
+    }
+}
+
+impl Filter for LevelRangeFilter {
+    fn filter(&self, record: &Record) -> Response {
+        if record.level().to_level_filter() <= self.min_level {
+            Response::Neutral
+        } else {
+            Response::Reject
+        }
+    }
+}
+
+impl fmt::Debug for LevelRangeFilter {
     fn fmt(&self, _f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         Ok(())
     }
@@ -169,18 +461,48 @@ impl fmt::Debug for CustomConsoleAppender {
     }
 }
 
+// Sibling of `CustomConsoleAppender` that, instead of writing to a terminal,
+// publishes each record onto a broadcast channel for remote admins to tail.
+struct BroadcastAppender {
+    sender: broadcast::Sender<LogEvent>,
+}
+
+impl Append for BroadcastAppender {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        // An error just means nobody is currently tailing; that's fine.
+        let _ = self.sender.send(LogEvent {
+            level: record.metadata().level().to_string(),
+            timestamp: current_time().to_rfc3339(),
+            target: record.module_path().unwrap_or_else(|| record.target()).to_owned(),
+            message: record.args().to_string(),
+        });
+        Ok(())
+    }
+
+    fn flush(&self) {}
+}
+
+impl fmt::Debug for BroadcastAppender {
+    fn fmt(&self, _f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        Ok(())
+    }
+}
+
 struct CustomLogRoller {
+    base: String,                 // archive base name, e.g. "latest" or "errors"
     name_info: Mutex<(u32, u32)>, // current day, log count for today
+    max_log_age_days: i64,        // delete archives older than this many days
+    max_log_files: usize,         // keep at most this many archives (newest first)
 }
 
 impl CustomLogRoller {
-    pub fn new() -> Self {
+    pub fn new(base: &str) -> Self {
         let mut max_index = 0;
 
         if let Ok(paths) = read_dir("./logs/") {
-            let today = format!("{}", current_time().format("%Y-%m-%d"));
+            let today = format!("{}-{}", base, current_time().format("%Y-%m-%d"));
 
-            // Find the logs that match today's date and determine the highest index ({date}-{index}.log).
+            // Find the logs that match today's date and determine the highest index ({base}-{date}-{index}.log.gz).
             for path in paths
                 .flatten()
                 .map(|entry| entry.file_name().into_string())
@@ -195,9 +517,68 @@ impl CustomLogRoller {
             }
         }
 
-        CustomLogRoller {
+        let roller = CustomLogRoller {
+            base: base.to_owned(),
             name_info: Mutex::new((current_time().ordinal(), max_index)),
+            max_log_age_days: DEFAULT_MAX_LOG_AGE_DAYS,
+            max_log_files: DEFAULT_MAX_LOG_FILES,
+        };
+
+        // Prune anything already over the limits before we start writing.
+        roller.enforce_retention();
+        roller
+    }
+
+    // Deletes archives for this roller's base that are older than
+    // `max_log_age_days` or beyond the newest `max_log_files`, keeping the
+    // newest by ({date}, {index}).
+    fn enforce_retention(&self) {
+        Self::prune(&self.base, self.max_log_age_days, self.max_log_files);
+    }
+
+    fn prune(base: &str, max_log_age_days: i64, max_log_files: usize) {
+        let prefix = format!("{}-", base);
+        let today = current_time().date_naive();
+
+        let mut archives = Vec::new();
+        if let Ok(paths) = read_dir("./logs/") {
+            for entry in paths.flatten() {
+                let name = match entry.file_name().into_string() {
+                    Ok(name) => name,
+                    Err(_) => continue,
+                };
+
+                if !name.starts_with(&prefix) || !name.ends_with(".log.gz") {
+                    continue;
+                }
+
+                if let Some((date, index)) = Self::archive_key(&name, &prefix) {
+                    archives.push((date, index, entry.path()));
+                }
+            }
         }
+
+        // Newest first so the first `max_log_files` entries are the keepers.
+        archives.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.cmp(&a.1)));
+
+        for (index, (date, _, path)) in archives.iter().enumerate() {
+            let too_old = (today - *date).num_days() > max_log_age_days;
+            let too_many = index >= max_log_files;
+            if too_old || too_many {
+                let _ = remove_file(path);
+            }
+        }
+    }
+
+    // Parses the `{date}` and `{index}` out of a `{base}-{date}-{index}.log.gz`
+    // archive name. Returns `None` for anything that doesn't match the scheme.
+    fn archive_key(name: &str, prefix: &str) -> Option<(NaiveDate, u32)> {
+        let stem = name.strip_prefix(prefix)?.strip_suffix(".log.gz")?;
+        let (date, index) = stem.rsplit_once('-')?;
+        Some((
+            NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?,
+            index.parse::<u32>().ok()?,
+        ))
     }
 
     fn index_from_path(path: &str) -> Option<u32> {
@@ -228,30 +609,43 @@ impl CustomLogRoller {
         }
 
         // Rename the file in case it's large and will take a while to compress
-        let log = "./logs/latest-tmp.log";
-        rename(file, log)?;
+        let log = format!("./logs/{}-tmp.log", self.base);
+        rename(file, &log)?;
 
         let output = format!(
-            "./logs/{}-{}.log.gz",
+            "./logs/{}-{}-{}.log.gz",
+            self.base,
             local_datetime.format("%Y-%m-%d"),
             guard.1
         );
 
+        // Captured by the compression step so it can prune old archives once the
+        // new one lands, whether or not the work is offloaded to a thread.
+        let base = self.base.clone();
+        let max_age = self.max_log_age_days;
+        let max_files = self.max_log_files;
+
         if threaded {
             thread::spawn(move || {
-                Self::try_compress_log(log, &output);
+                if Self::try_compress_log(&log, &output) {
+                    Self::prune(&base, max_age, max_files);
+                }
             });
-        } else {
-            Self::try_compress_log(log, &output);
+        } else if Self::try_compress_log(&log, &output) {
+            Self::prune(&base, max_age, max_files);
         }
 
         Ok(())
     }
 
-    // Attempts compress_log and prints an error if it fails
-    fn try_compress_log(input_path: &str, output_path: &str) {
-        if let Err(_) = Self::compress_log(Path::new(input_path), Path::new(output_path)) {
-            error!("Failed to compress log file");
+    // Attempts compress_log, returning whether it succeeded and printing an error if it fails
+    fn try_compress_log(input_path: &str, output_path: &str) -> bool {
+        match Self::compress_log(Path::new(input_path), Path::new(output_path)) {
+            Ok(()) => true,
+            Err(_) => {
+                error!("Failed to compress log file");
+                false
+            }
         }
     }
 
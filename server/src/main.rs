@@ -1,28 +1,53 @@
-use std::{error::Error, sync::Arc, time::Duration};
+use std::{
+    error::Error,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use futures::{channel::oneshot, SinkExt, StreamExt};
 use linefeed::{Interface, ReadResult};
-use log::error;
-use warp::{fs, ws::Ws, Filter};
+use log::{error, warn, Level, LevelFilter};
+use tokio::sync::{broadcast, mpsc};
+use warp::{
+    fs,
+    ws::{Message, WebSocket, Ws},
+    Filter,
+};
+
+use logging::LogEvent;
+use packets::{ClientBoundPackets, LogSubscription, ServerBoundPackets};
+use rooms::Rooms;
 
 mod logging;
 mod packets;
+mod rooms;
+
+// Hands out a unique id to each connected socket so rooms can track members.
+static NEXT_PLAYER_ID: AtomicUsize = AtomicUsize::new(1);
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let console_interface = Arc::new(Interface::new("SAP")?);
     console_interface.set_prompt("> ")?;
-    logging::init_logger(console_interface.clone())?;
+    let logger = logging::init_logger(console_interface.clone())?;
+    let log_filter = logger.filter;
 
-    let server_shutdown = start_server();
+    let server_shutdown = start_server(logger.log_events);
 
     loop {
         match console_interface.read_line_step(Some(Duration::from_millis(50))) {
             Ok(result) => match result {
-                Some(ReadResult::Input(command)) =>
-                    if command.to_ascii_lowercase() == "stop" {
+                Some(ReadResult::Input(command)) => {
+                    let trimmed = command.trim();
+                    if trimmed.to_ascii_lowercase() == "stop" {
                         break;
-                    },
+                    } else if let Some(spec) = trimmed.strip_prefix("log ") {
+                        logging::reconfigure(&log_filter, spec.trim());
+                    }
+                }
                 _ => {}
             },
             Err(e) => error!("Error reading console input: {}", e),
@@ -36,28 +61,25 @@ async fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn start_server() -> oneshot::Sender<()> {
-    let websocket = warp::path("ws")
+fn start_server(log_events: broadcast::Sender<LogEvent>) -> oneshot::Sender<()> {
+    let rooms = rooms::new_rooms();
+
+    // Remote admins tail the live log stream here. The first text frame may be a
+    // `LogSubscription` narrowing the stream by severity and/or module tag.
+    let log_stream = warp::path("ws")
+        .and(warp::path("logs"))
         .and(warp::ws())
-        .and(warp::addr::remote())
-        .map(|ws: Ws, _address| {
-            ws.on_upgrade(move |socket| async {
-                let (mut ws_tx, mut ws_rx) = socket.split();
-
-                while let Some(result) = ws_rx.next().await {
-                    let message = match result {
-                        Ok(message) => message,
-                        Err(e) => {
-                            error!("WS error {}", e);
-                            break;
-                        }
-                    };
+        .map(move |ws: Ws| {
+            let log_events = log_events.clone();
+            ws.on_upgrade(move |socket| handle_log_socket(socket, log_events))
+        });
 
-                    log::debug!("{:?}", message);
-                    ws_tx.send(message).await.unwrap();
-                    ws_tx.flush().await.unwrap();
-                }
-            })
+    let game_rooms = warp::any().map(move || rooms.clone());
+    let websocket = warp::path("ws")
+        .and(warp::ws())
+        .and(game_rooms)
+        .map(|ws: Ws, rooms: Rooms| {
+            ws.on_upgrade(move |socket| handle_game_socket(socket, rooms))
         });
 
     let html_hosting = fs::dir("client/out")
@@ -66,13 +88,190 @@ fn start_server() -> oneshot::Sender<()> {
 
     let (shutdown_hook, rx) = oneshot::channel::<()>();
 
-    let (_addr, server) = warp::serve(html_hosting.or(websocket)).bind_with_graceful_shutdown(
-        ([0, 0, 0, 0], 8080),
-        async {
+    // `log_stream` first so `/ws/logs` isn't swallowed by the `/ws` game route.
+    let (_addr, server) = warp::serve(html_hosting.or(log_stream).or(websocket))
+        .bind_with_graceful_shutdown(([0, 0, 0, 0], 8080), async {
             rx.await.ok();
-        },
-    );
+        });
 
     tokio::task::spawn(server);
     shutdown_hook
 }
+
+// Drives a single game connection: deserializes each frame into a
+// `ServerBoundPackets`, dispatches it against the shared room registry, and
+// cleans the player out of its room on disconnect.
+async fn handle_game_socket(socket: WebSocket, rooms: Rooms) {
+    let player_id = NEXT_PLAYER_ID.fetch_add(1, Ordering::Relaxed);
+    let (mut ws_tx, mut ws_rx) = socket.split();
+
+    // Per-connection outbound queue. A dedicated task serializes packets and
+    // writes them to the socket, so any room member can push to this player
+    // without sharing the underlying sink.
+    let (tx, mut rx) = mpsc::unbounded_channel::<ClientBoundPackets>();
+    tokio::task::spawn(async move {
+        while let Some(packet) = rx.recv().await {
+            match serde_json::to_string(&packet) {
+                Ok(json) =>
+                    if ws_tx.send(Message::text(json)).await.is_err() {
+                        break;
+                    },
+                Err(e) => error!("Failed to serialize packet: {}", e),
+            }
+        }
+    });
+
+    // The code of the room this player is currently in, for disconnect cleanup.
+    let mut current_room: Option<String> = None;
+
+    while let Some(result) = ws_rx.next().await {
+        let message = match result {
+            Ok(message) => message,
+            Err(e) => {
+                error!("WS error {}", e);
+                break;
+            }
+        };
+
+        if message.is_close() {
+            break;
+        }
+
+        if !message.is_text() {
+            continue;
+        }
+
+        let packet = match serde_json::from_str::<ServerBoundPackets>(message.to_str().unwrap_or(""))
+        {
+            Ok(packet) => packet,
+            Err(e) => {
+                warn!("Ignoring malformed packet: {}", e);
+                continue;
+            }
+        };
+
+        match packet {
+            ServerBoundPackets::CreateGame {
+                code,
+                password,
+                max_players,
+            } => {
+                let accepted =
+                    rooms::create_game(&rooms, code.clone(), password, max_players, player_id, tx.clone());
+                if accepted {
+                    leave_current_room(&rooms, &mut current_room, player_id, &code);
+                    current_room = Some(code);
+                }
+                let _ = tx.send(ClientBoundPackets::RoomResponse { accepted });
+            }
+            ServerBoundPackets::JoinGame { code, password } => {
+                let accepted = rooms::join_game(&rooms, &code, &password, player_id, tx.clone());
+                if accepted {
+                    leave_current_room(&rooms, &mut current_room, player_id, &code);
+                    current_room = Some(code);
+                }
+                let _ = tx.send(ClientBoundPackets::RoomResponse { accepted });
+            }
+            ServerBoundPackets::ChoosePack {} => {
+                // Accept the chosen pack and ask the client to register it.
+                let _ = tx.send(ClientBoundPackets::PackResponse { accepted: true });
+                let _ = tx.send(ClientBoundPackets::RegisterPack);
+            }
+        }
+    }
+
+    if let Some(code) = current_room {
+        rooms::remove_player(&rooms, &code, player_id);
+    }
+}
+
+// Drops the player from whatever room they were in before moving to `new_code`,
+// so a single connection never lingers in more than one room's member list.
+fn leave_current_room(
+    rooms: &Rooms,
+    current_room: &mut Option<String>,
+    player_id: usize,
+    new_code: &str,
+) {
+    if let Some(old_code) = current_room.take() {
+        if old_code != new_code {
+            rooms::remove_player(rooms, &old_code, player_id);
+        }
+    }
+}
+
+// Forwards broadcast log events to a single admin socket as JSON `LogMessage`
+// packets, honoring the optional filter sent as the socket's first frame.
+async fn handle_log_socket(socket: WebSocket, log_events: broadcast::Sender<LogEvent>) {
+    let (mut ws_tx, mut ws_rx) = socket.split();
+    let mut events = log_events.subscribe();
+
+    // Streams everything until the client sends a `LogSubscription` to narrow it
+    // (usually the first frame, but accepted at any time).
+    let mut subscription = LogSubscription::default();
+
+    loop {
+        tokio::select! {
+            // An incoming frame is either a new filter or a sign the client left.
+            incoming = ws_rx.next() => match incoming {
+                Some(Ok(message)) =>
+                    if message.is_text() {
+                        if let Ok(sub) =
+                            serde_json::from_str::<LogSubscription>(message.to_str().unwrap_or(""))
+                        {
+                            subscription = sub;
+                        }
+                    } else if message.is_close() {
+                        break;
+                    },
+                _ => break,
+            },
+            event = events.recv() => match event {
+                Ok(event) => {
+                    if !subscription_matches(&subscription, &event) {
+                        continue;
+                    }
+
+                    let packet = ClientBoundPackets::LogMessage {
+                        level: event.level,
+                        timestamp: event.timestamp,
+                        target: event.target,
+                        message: event.message,
+                    };
+
+                    match serde_json::to_string(&packet) {
+                        Ok(json) =>
+                            if ws_tx.send(Message::text(json)).await.is_err() {
+                                break;
+                            },
+                        Err(e) => error!("Failed to serialize log packet: {}", e),
+                    }
+                }
+                // A slow client fell behind; skip the dropped records and continue.
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        }
+    }
+}
+
+// Whether a log event passes the admin's optional severity/tag filter.
+fn subscription_matches(subscription: &LogSubscription, event: &LogEvent) -> bool {
+    if let Some(min_level) = &subscription.min_level {
+        if let (Ok(min), Ok(level)) =
+            (min_level.parse::<LevelFilter>(), event.level.parse::<Level>())
+        {
+            if level.to_level_filter() > min {
+                return false;
+            }
+        }
+    }
+
+    if let Some(tag) = &subscription.tag {
+        if !event.target.contains(tag.as_str()) {
+            return false;
+        }
+    }
+
+    true
+}
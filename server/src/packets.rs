@@ -6,6 +6,7 @@ pub enum ServerBoundPackets {
     ChoosePack {},
     JoinGame {
         code: String,
+        password: String,
     },
     CreateGame {
         code: String,
@@ -14,9 +15,33 @@ pub enum ServerBoundPackets {
     },
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Clone)]
 #[serde(tag = "packet_type", content = "packet_data")]
 pub enum ClientBoundPackets {
     PackResponse { accepted: bool },
     RegisterPack,
+    LogMessage {
+        level: String,
+        timestamp: String,
+        target: String,
+        message: String,
+    },
+    // Reply to a `CreateGame`/`JoinGame` attempt.
+    RoomResponse {
+        accepted: bool,
+    },
+    // Broadcast to every member of a room whenever its membership changes.
+    GameState {
+        code: String,
+        players: u8,
+    },
+}
+
+// Optional filter an admin sends as the first frame on `/ws/logs` to narrow the
+// stream: a minimum severity (`"warn"`, `"info"`, ...) and/or a module tag the
+// record's target must contain. Absent fields impose no restriction.
+#[derive(Deserialize, Default)]
+pub struct LogSubscription {
+    pub min_level: Option<String>,
+    pub tag: Option<String>,
 }
@@ -0,0 +1,117 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::mpsc;
+
+use crate::packets::ClientBoundPackets;
+
+// The shared registry of active game rooms, keyed by game code.
+pub type Rooms = Arc<Mutex<HashMap<String, Room>>>;
+
+// A channel used to push packets to a single connected player.
+pub type PlayerSender = mpsc::UnboundedSender<ClientBoundPackets>;
+
+// Creates an empty room registry.
+pub fn new_rooms() -> Rooms {
+    Arc::new(Mutex::new(HashMap::new()))
+}
+
+// A single game lobby: its join password, capacity, and connected players.
+pub struct Room {
+    password: String,
+    max_players: u8,
+    players: HashMap<usize, PlayerSender>,
+}
+
+impl Room {
+    fn new(password: String, max_players: u8) -> Self {
+        Room {
+            password,
+            max_players,
+            players: HashMap::new(),
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.players.len() >= self.max_players as usize
+    }
+
+    // Sends the current player count to every member so clients stay in sync.
+    fn broadcast_state(&self, code: &str) {
+        let state = ClientBoundPackets::GameState {
+            code: code.to_owned(),
+            players: self.players.len() as u8,
+        };
+
+        for sender in self.players.values() {
+            // A send error just means that player has since disconnected.
+            let _ = sender.send(state.clone());
+        }
+    }
+}
+
+// Handles `CreateGame`: inserts a new room unless the code is already taken or
+// the capacity is nonsensical, returning whether it was created. The creator
+// becomes the room's first member.
+pub fn create_game(
+    rooms: &Rooms,
+    code: String,
+    password: String,
+    max_players: u8,
+    player_id: usize,
+    sender: PlayerSender,
+) -> bool {
+    let mut rooms = rooms.lock().expect("Rooms lock poisoned.");
+
+    if rooms.contains_key(&code) || max_players == 0 {
+        return false;
+    }
+
+    let mut room = Room::new(password, max_players);
+    room.players.insert(player_id, sender);
+    room.broadcast_state(&code);
+    rooms.insert(code, room);
+    true
+}
+
+// Handles `JoinGame`: validates the code, password, and capacity, attaching the
+// player and broadcasting the new state on success.
+pub fn join_game(
+    rooms: &Rooms,
+    code: &str,
+    password: &str,
+    player_id: usize,
+    sender: PlayerSender,
+) -> bool {
+    let mut rooms = rooms.lock().expect("Rooms lock poisoned.");
+
+    let room = match rooms.get_mut(code) {
+        Some(room) => room,
+        None => return false,
+    };
+
+    if room.password != password || room.is_full() {
+        return false;
+    }
+
+    room.players.insert(player_id, sender);
+    room.broadcast_state(code);
+    true
+}
+
+// Removes a disconnected player from its room, deleting the room once it is
+// empty and otherwise broadcasting the reduced state to the remaining members.
+pub fn remove_player(rooms: &Rooms, code: &str, player_id: usize) {
+    let mut rooms = rooms.lock().expect("Rooms lock poisoned.");
+
+    if let Some(room) = rooms.get_mut(code) {
+        room.players.remove(&player_id);
+        if room.players.is_empty() {
+            rooms.remove(code);
+        } else {
+            room.broadcast_state(code);
+        }
+    }
+}